@@ -0,0 +1,91 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! A single chain-tip watcher shared by every subsystem that needs to react to new block templates. Both
+//! [`crate::stratum`] (`mining.notify`) and [`crate::work_notify`] (webhook push) used to poll `node_service`
+//! for the chain tip independently; this module polls once and fans the result out, so the two front-ends stay in
+//! lock-step with each other the same way [`crate::commands::CommandHandler`] keeps the REPL and JSON-RPC front-ends
+//! in lock-step for request/response commands.
+
+use crate::LOG_TARGET;
+use log::*;
+use tari_core::{base_node::LocalNodeCommsInterface, blocks::NewBlockTemplate, chain_storage::ChainMetadata};
+use tokio::{runtime, sync::broadcast};
+
+/// A new block template observed at a fresh chain tip.
+#[derive(Clone, Debug)]
+pub struct ChainTipEvent {
+    pub metadata: ChainMetadata,
+    pub template: NewBlockTemplate,
+}
+
+/// Polls `node_service` for the chain tip and broadcasts a [`ChainTipEvent`] to every subscriber whenever it moves.
+/// Cheap to clone; every clone shares the same background poll task and subscriber set.
+#[derive(Clone)]
+pub struct ChainTipWatcher {
+    event_tx: broadcast::Sender<ChainTipEvent>,
+}
+
+impl ChainTipWatcher {
+    /// Spawns the background poll task on `executor` and returns a handle that can be subscribed to any number of
+    /// times.
+    pub fn spawn(executor: &runtime::Handle, node_service: LocalNodeCommsInterface) -> Self {
+        let (event_tx, _) = broadcast::channel(16);
+        let watcher = Self { event_tx: event_tx.clone() };
+        executor.spawn(Self::watch(node_service, event_tx));
+        watcher
+    }
+
+    /// Subscribes to chain tip events. Each subscriber gets its own independent receiver.
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainTipEvent> {
+        self.event_tx.subscribe()
+    }
+
+    async fn watch(mut node_service: LocalNodeCommsInterface, event_tx: broadcast::Sender<ChainTipEvent>) {
+        let mut last_tip = None;
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            let metadata = match node_service.get_metadata().await {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    warn!(target: LOG_TARGET, "chain-tip-watch: failed to read chain metadata: {}", err);
+                    continue;
+                },
+            };
+            if Some(&metadata.best_block) == last_tip.as_ref() {
+                continue;
+            }
+            last_tip = Some(metadata.best_block.clone());
+
+            let template = match node_service.get_new_block_template().await {
+                Ok(template) => template,
+                Err(err) => {
+                    warn!(target: LOG_TARGET, "chain-tip-watch: failed to build block template: {}", err);
+                    continue;
+                },
+            };
+            // No receivers (e.g. nobody has subscribed yet) is not an error; just means nobody is listening.
+            let _ = event_tx.send(ChainTipEvent { metadata, template });
+        }
+    }
+}
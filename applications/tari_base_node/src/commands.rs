@@ -0,0 +1,416 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! The command layer backing both the interactive [`crate::parser::Parser`] and the JSON-RPC server in
+//! [`crate::rpc`]. Every [`BaseNodeCommand`](crate::parser::BaseNodeCommand) has a matching method here that talks
+//! to the node's service handles and returns a typed result instead of printing to stdout, so callers can decide
+//! how (or whether) to render it. This includes ownership of the long-running Stratum server and work-notify
+//! webhook subsystems, so either front-end can start/stop them identically rather than one holding state the other
+//! can't see.
+
+use crate::{chain_tip::ChainTipWatcher, stratum, work_notify::WorkNotifier};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+};
+use tari_comms::{
+    connection_manager::{ConnectionManagerError, ConnectionManagerRequester},
+    peer_manager::{Peer, PeerManager, PeerManagerError},
+    types::CommsPublicKey,
+    NodeIdentity,
+};
+use tari_core::{
+    base_node::{comms_interface::CommsInterfaceError, LocalNodeCommsInterface},
+    blocks::BlockHeader,
+    chain_storage::ChainMetadata,
+    tari_utilities::hex::Hex,
+    transactions::tari_amount::MicroTari,
+};
+use tari_wallet::{
+    output_manager_service::{error::OutputManagerError, handle::OutputManagerHandle},
+    transaction_service::{error::TransactionServiceError, handle::TransactionServiceHandle},
+    util::emoji::EmojiId,
+};
+use thiserror::Error;
+use tokio::{
+    runtime,
+    sync::{broadcast, RwLock},
+};
+
+/// Default fee-per-gram used for outgoing transactions when the caller does not supply one. Like a supplied fee, it
+/// is raised to the node-wide minimum by [`CommandHandler::send_tari`] when that minimum is higher.
+pub const DEFAULT_FEE_PER_GRAM: MicroTari = MicroTari(25);
+
+/// Name of the file the node-wide fee policy is persisted to, relative to the node's data directory.
+const FEE_POLICY_FILE: &str = "fee_policy.json";
+
+/// A connection younger than this counts as "active" in [`CommandHandler::get_network_status`]. This is a rough
+/// recently-connected approximation, not real liveness (last-message time) -- `ConnectionManagerRequester` does not
+/// expose that here.
+const ACTIVE_CONNECTION_AGE: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// The node-wide minimum fee-per-gram set via `set-fee-policy`. Applied as the floor for outgoing transactions
+/// built through [`CommandHandler::send_tari`], and as the threshold [`CommandHandler::should_propagate`] compares
+/// against. Relaying of incoming transactions does not consult it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FeePolicy {
+    pub min_fee_per_gram: MicroTari,
+}
+
+impl Default for FeePolicy {
+    fn default() -> Self {
+        FeePolicy {
+            min_fee_per_gram: MicroTari::from(0),
+        }
+    }
+}
+
+impl FeePolicy {
+    fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Errors returned by the command layer. Most map onto the errors already returned by the service handles;
+/// `InvalidArgument` is raised by the command layer itself for bad input or a request that conflicts with its
+/// state (e.g. starting a Stratum server that is already running).
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("Invalid command argument: {0}")]
+    InvalidArgument(String),
+    #[error("Error communicating with the wallet output manager: {0}")]
+    OutputManager(#[from] OutputManagerError),
+    #[error("Error communicating with the wallet transaction service: {0}")]
+    TransactionService(#[from] TransactionServiceError),
+    #[error("Error communicating with the base node: {0}")]
+    CommsInterface(#[from] CommsInterfaceError),
+    #[error("Error reading peers: {0}")]
+    PeerManager(#[from] PeerManagerError),
+    #[error("Error reading connections: {0}")]
+    ConnectionManager(#[from] ConnectionManagerError),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// The result of a successful `get-balance` command.
+pub type Balance = tari_wallet::output_manager_service::handle::Balance;
+
+/// The result of a successful `send-tari` command.
+#[derive(Debug, Clone, Serialize)]
+pub struct SendTariResult {
+    pub amount: MicroTari,
+    pub fee_per_gram: MicroTari,
+    pub destination: String,
+    pub message: String,
+}
+
+/// One entry in the result of `list-connections`/`get-network-status`: the detail a connected peer's
+/// [`tari_comms::peer_connection::PeerConnection`] can supply beyond its raw `Display` output.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub peer_public_key: String,
+    pub address: String,
+    pub direction: String,
+    pub age_seconds: u64,
+}
+
+/// The result of a successful `get-network-status` command: the connected/active/max distinction operators need,
+/// on top of the raw peer and connection counts `list-peers`/`list-connections` already report.
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkStatus {
+    pub num_known_peers: usize,
+    pub num_connected_peers: usize,
+    pub num_active_connections: usize,
+    pub max_connections: usize,
+    pub connections: Vec<ConnectionInfo>,
+}
+
+/// Holds clones of the service handles a node command needs and knows how to execute every
+/// [`BaseNodeCommand`](crate::parser::BaseNodeCommand). Both [`crate::parser::Parser`] (interactive/batch) and
+/// [`crate::rpc`] (JSON-RPC) hold one of these and delegate to it rather than duplicating the handler bodies.
+#[derive(Clone)]
+pub struct CommandHandler {
+    node_identity: Arc<NodeIdentity>,
+    peer_manager: Arc<PeerManager>,
+    connection_manager: ConnectionManagerRequester,
+    wallet_output_service: OutputManagerHandle,
+    node_service: LocalNodeCommsInterface,
+    wallet_transaction_service: TransactionServiceHandle,
+    enable_miner: Arc<std::sync::atomic::AtomicBool>,
+    fee_policy: Arc<RwLock<FeePolicy>>,
+    fee_policy_path: PathBuf,
+    max_connections: usize,
+    chain_tip: ChainTipWatcher,
+    work_notifier: WorkNotifier,
+    work_notify_shutdown: broadcast::Sender<()>,
+    stratum_server: Arc<RwLock<Option<stratum::StratumServerHandle>>>,
+}
+
+impl CommandHandler {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        node_identity: Arc<NodeIdentity>,
+        peer_manager: Arc<PeerManager>,
+        connection_manager: ConnectionManagerRequester,
+        wallet_output_service: OutputManagerHandle,
+        node_service: LocalNodeCommsInterface,
+        wallet_transaction_service: TransactionServiceHandle,
+        enable_miner: Arc<std::sync::atomic::AtomicBool>,
+        data_dir: PathBuf,
+        max_connections: usize,
+        executor: runtime::Handle,
+    ) -> Self {
+        let fee_policy_path = data_dir.join(FEE_POLICY_FILE);
+        let fee_policy = FeePolicy::load(&fee_policy_path);
+
+        let chain_tip = ChainTipWatcher::spawn(&executor, node_service.clone());
+        let work_notifier = WorkNotifier::new();
+        let (work_notify_shutdown, work_notify_shutdown_rx) = broadcast::channel(1);
+        executor.spawn(work_notifier.clone().run(chain_tip.clone(), work_notify_shutdown_rx));
+
+        Self {
+            node_identity,
+            peer_manager,
+            connection_manager,
+            wallet_output_service,
+            node_service,
+            wallet_transaction_service,
+            enable_miner,
+            fee_policy: Arc::new(RwLock::new(fee_policy)),
+            fee_policy_path,
+            max_connections,
+            chain_tip,
+            work_notifier,
+            work_notify_shutdown,
+            stratum_server: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn whoami(&self) -> Arc<NodeIdentity> {
+        self.node_identity.clone()
+    }
+
+    pub async fn get_balance(&self) -> Result<Balance, CommandError> {
+        let mut handler = self.wallet_output_service.clone();
+        Ok(handler.get_balance().await?)
+    }
+
+    pub async fn get_chain_metadata(&self) -> Result<ChainMetadata, CommandError> {
+        let mut handler = self.node_service.clone();
+        Ok(handler.get_metadata().await?)
+    }
+
+    pub async fn list_peers(&self) -> Result<Vec<Peer>, CommandError> {
+        Ok(self.peer_manager.flood_peers().await?)
+    }
+
+    pub async fn list_connections(&self) -> Result<Vec<tari_comms::peer_connection::PeerConnection>, CommandError> {
+        let mut connection_manager = self.connection_manager.clone();
+        Ok(connection_manager.get_active_connections().await?)
+    }
+
+    /// The per-connection detail (direction, address, age) that a plain `Display` of a connection does not surface.
+    pub async fn list_connections_detailed(&self) -> Result<Vec<ConnectionInfo>, CommandError> {
+        let connections = self.list_connections().await?;
+        Ok(connections
+            .iter()
+            .map(|conn| ConnectionInfo {
+                peer_public_key: conn.peer_public_key().to_hex(),
+                address: conn.address().to_string(),
+                direction: format!("{:?}", conn.direction()),
+                age_seconds: conn.age().as_secs(),
+            })
+            .collect())
+    }
+
+    /// Reports the connected/active/max distinction an operator needs at a glance: how many peers are known at
+    /// all, how many currently have a connection, how many of those connections are active, and the configured
+    /// connection slot limit. `ConnectionManagerRequester` does not expose the configured limit itself, so
+    /// `max_connections` is the value passed in at construction (sourced from the node's config). "Active" is
+    /// approximated by [`ACTIVE_CONNECTION_AGE`] rather than real liveness -- see its doc comment.
+    pub async fn get_network_status(&self) -> Result<NetworkStatus, CommandError> {
+        let known_peers = self.list_peers().await?;
+        let connections = self.list_connections_detailed().await?;
+        let num_active_connections = connections
+            .iter()
+            .filter(|c| c.age_seconds < ACTIVE_CONNECTION_AGE.as_secs())
+            .count();
+        Ok(NetworkStatus {
+            num_known_peers: known_peers.len(),
+            num_connected_peers: connections.len(),
+            num_active_connections,
+            max_connections: self.max_connections,
+            connections,
+        })
+    }
+
+    pub async fn list_headers(&self, num_headers: usize) -> Result<Vec<BlockHeader>, CommandError> {
+        let mut handler = self.node_service.clone();
+        let max_height = handler.get_metadata().await?.height_of_longest_chain.unwrap_or(0);
+        let heights = (0..max_height + 1).rev().take(num_headers).collect();
+        Ok(handler.get_header(heights).await?)
+    }
+
+    pub fn toggle_mining(&self) -> bool {
+        let new_state = !self.enable_miner.load(Ordering::SeqCst);
+        self.enable_miner.store(new_state, Ordering::SeqCst);
+        new_state
+    }
+
+    pub async fn send_tari(
+        &self,
+        amount: MicroTari,
+        fee_per_gram: MicroTari,
+        destination: &str,
+        message: String,
+    ) -> Result<SendTariResult, CommandError> {
+        let dest_pubkey = CommsPublicKey::from_hex(destination)
+            .or_else(|_| EmojiId::str_to_pubkey(destination))
+            .map_err(|_| CommandError::InvalidArgument(format!("'{}' is not a valid public key", destination)))?;
+        let min_fee_per_gram = self.fee_policy.read().await.min_fee_per_gram;
+        let fee_per_gram = fee_per_gram.max(min_fee_per_gram);
+        let mut handler = self.wallet_transaction_service.clone();
+        handler
+            .send_transaction(dest_pubkey.clone(), amount, fee_per_gram, message.clone())
+            .await?;
+        Ok(SendTariResult {
+            amount,
+            fee_per_gram,
+            destination: dest_pubkey.to_hex(),
+            message,
+        })
+    }
+
+    /// Returns the currently configured node-wide fee policy.
+    pub async fn get_fee_policy(&self) -> FeePolicy {
+        *self.fee_policy.read().await
+    }
+
+    /// Sets and persists the node-wide minimum fee-per-gram, applied to outgoing transactions from now on and used
+    /// as the threshold in [`Self::should_propagate`].
+    pub async fn set_fee_policy(&self, min_fee_per_gram: MicroTari) -> Result<FeePolicy, CommandError> {
+        let policy = FeePolicy { min_fee_per_gram };
+        policy
+            .save(&self.fee_policy_path)
+            .map_err(|e| CommandError::InvalidArgument(format!("could not persist fee policy: {}", e)))?;
+        *self.fee_policy.write().await = policy;
+        Ok(policy)
+    }
+
+    /// Starts the Stratum mining server on `bind_address`, or returns an error if one is already running.
+    pub async fn start_stratum(&self, bind_address: SocketAddr) -> Result<(), CommandError> {
+        let mut stratum_server = self.stratum_server.write().await;
+        if stratum_server.is_some() {
+            return Err(CommandError::InvalidArgument("Stratum server is already running".into()));
+        }
+        let handle = stratum::start(bind_address, self.chain_tip.clone(), self.node_service.clone()).await?;
+        *stratum_server = Some(handle);
+        Ok(())
+    }
+
+    /// Stops the Stratum mining server if one is running. Returns whether one was actually running.
+    pub async fn stop_stratum(&self) -> bool {
+        match self.stratum_server.write().await.take() {
+            Some(handle) => {
+                handle.stop();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Registers a work-notify webhook URL.
+    pub async fn work_notify_add(&self, url: String) {
+        self.work_notifier.add_url(url).await;
+    }
+
+    /// Deregisters a work-notify webhook URL. Returns whether it was registered.
+    pub async fn work_notify_remove(&self, url: &str) -> bool {
+        self.work_notifier.remove_url(url).await
+    }
+
+    /// Lists the registered work-notify webhook URLs.
+    pub async fn work_notify_list(&self) -> Vec<String> {
+        self.work_notifier.list_urls().await
+    }
+
+    /// Stops the Stratum server (if running) and the work-notify background task. Called once, on node shutdown.
+    pub async fn shutdown_background_tasks(&self) {
+        self.stop_stratum().await;
+        let _ = self.work_notify_shutdown.send(());
+    }
+
+    /// Whether an incoming transaction offering `fee_per_gram` meets the node's configured minimum.
+    ///
+    /// Nothing in the mempool or transaction validation path calls this method, so it does not gate the relaying
+    /// or servicing of received transactions.
+    pub async fn should_propagate(&self, fee_per_gram: MicroTari) -> bool {
+        meets_fee_floor(fee_per_gram, self.fee_policy.read().await.min_fee_per_gram)
+    }
+}
+
+/// Pure comparison backing [`CommandHandler::should_propagate`], split out so the threshold logic can be unit
+/// tested without constructing a [`CommandHandler`].
+fn meets_fee_floor(fee_per_gram: MicroTari, min_fee_per_gram: MicroTari) -> bool {
+    fee_per_gram >= min_fee_per_gram
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn meets_fee_floor_boundary() {
+        assert!(meets_fee_floor(MicroTari::from(25), MicroTari::from(25)));
+        assert!(meets_fee_floor(MicroTari::from(30), MicroTari::from(25)));
+        assert!(!meets_fee_floor(MicroTari::from(10), MicroTari::from(25)));
+    }
+
+    #[test]
+    fn fee_policy_load_missing_file_returns_default() {
+        let path = Path::new("/tmp/this-fee-policy-file-should-not-exist.json");
+        assert_eq!(FeePolicy::load(path).min_fee_per_gram, MicroTari::from(0));
+    }
+
+    #[test]
+    fn fee_policy_save_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("fee_policy_test_{}.json", std::process::id()));
+        let policy = FeePolicy {
+            min_fee_per_gram: MicroTari::from(42),
+        };
+        policy.save(&path).expect("failed to save fee policy");
+
+        let loaded = FeePolicy::load(&path);
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.min_fee_per_gram, policy.min_fee_per_gram);
+    }
+}
@@ -21,7 +21,10 @@
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use super::LOG_TARGET;
-use crate::builder::NodeContainer;
+use crate::{
+    builder::NodeContainer,
+    commands::{CommandError, CommandHandler, DEFAULT_FEE_PER_GRAM},
+};
 use log::*;
 use rustyline::{
     completion::Completer,
@@ -32,6 +35,7 @@ use rustyline::{
 };
 use rustyline_derive::{Helper, Highlighter, Validator};
 use std::{
+    net::SocketAddr,
     str::FromStr,
     string::ToString,
     sync::{
@@ -41,22 +45,7 @@ use std::{
 };
 use strum::IntoEnumIterator;
 use strum_macros::{Display, EnumIter, EnumString};
-use tari_comms::{
-    connection_manager::ConnectionManagerRequester,
-    peer_manager::PeerManager,
-    types::CommsPublicKey,
-    NodeIdentity,
-};
-use tari_core::{
-    base_node::LocalNodeCommsInterface,
-    tari_utilities::hex::Hex,
-    transactions::tari_amount::{uT, MicroTari},
-};
-use tari_wallet::{
-    output_manager_service::handle::OutputManagerHandle,
-    transaction_service::handle::TransactionServiceHandle,
-    util::emoji::EmojiId,
-};
+use tari_core::transactions::tari_amount::MicroTari;
 use tokio::runtime;
 
 /// Enum representing commands used by the basenode
@@ -70,26 +59,45 @@ pub enum BaseNodeCommand {
     ListPeers,
     ListConnections,
     ListHeaders,
+    GetNetworkStatus,
     Whoami,
     ToggleMining,
+    Stratum,
+    WorkNotify,
+    SetFeePolicy,
     Quit,
     Exit,
 }
 
+/// Selects how [`Parser::run_batch`] renders each command's result: `Text` prints the bare result value as JSON (or
+/// `Error: ...` on failure), not the prose the interactive REPL prints; `Json` emits a single structured object per
+/// command so scripts can parse it and branch on `success`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("'{}' is not a valid output format, expected 'text' or 'json'", s)),
+        }
+    }
+}
+
 /// This is used to parse commands from the user and execute them
 #[derive(Helper, Validator, Highlighter)]
 pub struct Parser {
     executor: runtime::Handle,
-    node_identity: Arc<NodeIdentity>,
-    peer_manager: Arc<PeerManager>,
-    connection_manager: ConnectionManagerRequester,
     shutdown_flag: Arc<AtomicBool>,
     commands: Vec<String>,
     hinter: HistoryHinter,
-    wallet_output_service: OutputManagerHandle,
-    node_service: LocalNodeCommsInterface,
-    wallet_transaction_service: TransactionServiceHandle,
-    enable_miner: Arc<AtomicBool>,
+    command_handler: CommandHandler,
 }
 
 // This will go through all instructions and look for potential matches
@@ -122,18 +130,162 @@ impl Hinter for Parser {
 impl Parser {
     /// creates a new parser struct
     pub fn new(executor: runtime::Handle, ctx: &NodeContainer) -> Self {
+        let command_handler = CommandHandler::new(
+            ctx.node_identity(),
+            ctx.comms().peer_manager(),
+            ctx.comms().connection_manager(),
+            ctx.output_manager(),
+            ctx.local_node(),
+            ctx.wallet_transaction_service(),
+            ctx.miner_enabled(),
+            ctx.base_path(),
+            ctx.config().max_connections(),
+            executor.clone(),
+        );
         Parser {
             executor,
-            node_identity: ctx.node_identity(),
-            peer_manager: ctx.comms().peer_manager(),
-            connection_manager: ctx.comms().connection_manager(),
             shutdown_flag: ctx.interrupt_flag(),
             commands: BaseNodeCommand::iter().map(|x| x.to_string()).collect(),
             hinter: HistoryHinter {},
-            wallet_output_service: ctx.output_manager(),
-            node_service: ctx.local_node(),
-            wallet_transaction_service: ctx.wallet_transaction_service(),
-            enable_miner: ctx.miner_enabled(),
+            command_handler,
+        }
+    }
+
+    /// Gives out a clone of the command layer backing this parser, e.g. so a JSON-RPC server can be started against
+    /// the same service handles.
+    pub fn command_handler(&self) -> CommandHandler {
+        self.command_handler.clone()
+    }
+
+    /// Runs a sequence of commands non-interactively (e.g. passed as process arguments or piped on stdin) and
+    /// returns a process exit code: `0` if every command succeeded, `1` if any failed. Unlike the REPL's
+    /// `handle_command`, each command is awaited to completion before the next one starts so a script can rely on
+    /// commands running in order and on the emitted result reflecting what actually happened.
+    pub async fn run_batch(&mut self, commands: Vec<String>, format: OutputFormat) -> i32 {
+        let mut results = Vec::with_capacity(commands.len());
+        for command_str in commands {
+            let mut args = command_str.split(' ');
+            let command = match BaseNodeCommand::from_str(args.next().unwrap_or("help")) {
+                Ok(command) => command,
+                Err(_) => {
+                    self.emit_result(format, Err(CommandError::InvalidArgument(format!(
+                        "'{}' is not a valid command",
+                        command_str
+                    ))));
+                    results.push(false);
+                    continue;
+                },
+            };
+            results.push(self.process_command_sync(command, args, format).await);
+        }
+        batch_exit_code(&results)
+    }
+
+    // Executes a single command to completion and renders its result in the requested format. Returns whether the
+    // command succeeded, so `run_batch` can compute the overall exit code.
+    async fn process_command_sync<'a, I: Iterator<Item = &'a str>>(
+        &mut self,
+        command: BaseNodeCommand,
+        mut args: I,
+        format: OutputFormat,
+    ) -> bool {
+        use BaseNodeCommand::*;
+        let result: Result<serde_json::Value, CommandError> = match command {
+            Help => Ok(serde_json::json!({ "commands": self.commands })),
+            GetBalance => self.command_handler.get_balance().await.map(|b| serde_json::json!({
+                "available": b.available_balance,
+                "pending_incoming": b.pending_incoming_balance,
+                "pending_outgoing": b.pending_outgoing_balance,
+            })),
+            GetChainMetadata => self
+                .command_handler
+                .get_chain_metadata()
+                .await
+                .and_then(|m| serde_json::to_value(m).map_err(|e| CommandError::InvalidArgument(e.to_string()))),
+            ListPeers => self.command_handler.list_peers().await.map(|peers| {
+                serde_json::json!({ "count": peers.len(), "peers": peers.iter().map(|p| p.to_string()).collect::<Vec<_>>() })
+            }),
+            ListConnections => self
+                .command_handler
+                .list_connections_detailed()
+                .await
+                .and_then(|conns| serde_json::to_value(conns).map_err(|e| CommandError::InvalidArgument(e.to_string()))),
+            ListHeaders => {
+                let max_headers = args.next().unwrap_or("1").parse().unwrap_or(1);
+                self.command_handler.list_headers(max_headers).await.map(|headers| {
+                    serde_json::json!(headers.iter().map(|h| format!("{}", h)).collect::<Vec<_>>())
+                })
+            },
+            GetNetworkStatus => self
+                .command_handler
+                .get_network_status()
+                .await
+                .and_then(|s| serde_json::to_value(s).map_err(|e| CommandError::InvalidArgument(e.to_string()))),
+            Whoami => Ok(serde_json::json!({ "identity": self.command_handler.whoami().to_string() })),
+            ToggleMining => Ok(serde_json::json!({ "mining_enabled": self.command_handler.toggle_mining() })),
+            SendTari => {
+                let command_arg = args.take(4).collect::<Vec<&str>>();
+                if command_arg.len() < 2 {
+                    Err(CommandError::InvalidArgument(
+                        "usage: send-tari [amount] [destination] [fee-per-gram] [message]".into(),
+                    ))
+                } else {
+                    match command_arg[0].parse::<u64>() {
+                        Err(_) => Err(CommandError::InvalidArgument("please enter a valid amount of tari".into())),
+                        Ok(amount) => {
+                            let fee_per_gram = command_arg
+                                .get(2)
+                                .and_then(|s| s.parse::<u64>().ok())
+                                .map(Into::into)
+                                .unwrap_or(DEFAULT_FEE_PER_GRAM);
+                            let message = command_arg.get(3).map(|s| s.to_string()).unwrap_or_default();
+                            self.command_handler
+                                .send_tari(amount.into(), fee_per_gram, command_arg[1], message)
+                                .await
+                                .and_then(|r| {
+                                    serde_json::to_value(r).map_err(|e| CommandError::InvalidArgument(e.to_string()))
+                                })
+                        },
+                    }
+                }
+            },
+            Stratum | WorkNotify => Err(CommandError::InvalidArgument(
+                "this command manages a long-running subsystem and is not supported in batch mode".into(),
+            )),
+            SetFeePolicy => match args.next().and_then(|fee| fee.parse::<u64>().ok()) {
+                Some(fee) => self
+                    .command_handler
+                    .set_fee_policy(fee.into())
+                    .await
+                    .and_then(|p| serde_json::to_value(p).map_err(|e| CommandError::InvalidArgument(e.to_string()))),
+                None => Err(CommandError::InvalidArgument(
+                    "usage: set-fee-policy [minimum fee-per-gram]".into(),
+                )),
+            },
+            Exit | Quit => {
+                self.command_handler.shutdown_background_tasks().await;
+                self.shutdown_flag.store(true, Ordering::SeqCst);
+                Ok(serde_json::json!({ "shutdown": true }))
+            },
+        };
+        let success = result.is_ok();
+        self.emit_result(format, result);
+        success
+    }
+
+    fn emit_result(&self, format: OutputFormat, result: Result<serde_json::Value, CommandError>) {
+        match format {
+            OutputFormat::Json => {
+                let payload = match result {
+                    Ok(value) => serde_json::json!({ "success": true, "result": value }),
+                    Err(e) => serde_json::json!({ "success": false, "error": e.to_string() }),
+                };
+                println!("{}", payload);
+            },
+            OutputFormat::Text => match result {
+                Ok(value) => println!("{}", value),
+                Err(e) => println!("Error: {}", e),
+            },
         }
     }
 
@@ -175,9 +327,21 @@ impl Parser {
             ListHeaders => {
                 self.process_list_headers(args);
             },
+            GetNetworkStatus => {
+                self.process_get_network_status();
+            },
             ToggleMining => {
                 self.process_toggle_mining();
             },
+            Stratum => {
+                self.process_stratum(args);
+            },
+            WorkNotify => {
+                self.process_work_notify(args);
+            },
+            SetFeePolicy => {
+                self.process_set_fee_policy(args);
+            },
             Whoami => {
                 self.process_whoami();
             },
@@ -187,6 +351,7 @@ impl Parser {
                     target: LOG_TARGET,
                     "Termination signal received from user. Shutting node down."
                 );
+                self.executor.block_on(self.command_handler.shutdown_background_tasks());
                 self.shutdown_flag.store(true, Ordering::SeqCst);
             },
         }
@@ -206,7 +371,7 @@ impl Parser {
             },
             SendTari => {
                 println!("Sends an amount of Tari to a address call this command via:");
-                println!("send_tari [amount of tari to send] [public key to send to]");
+                println!("send-tari [amount of tari to send] [public key to send to] [fee-per-gram] [message]");
             },
             GetChainMetadata => {
                 println!("Gets your base node chain meta data");
@@ -220,9 +385,26 @@ impl Parser {
             ListHeaders => {
                 println!("List the last headers up to a maximum of 10 of the current chain");
             },
+            GetNetworkStatus => {
+                println!("Reports known/connected/active peer counts, the configured connection limit, and");
+                println!("per-connection direction, address and age");
+            },
             ToggleMining => {
                 println!("Enable or disable the miner on this node, calling this command will toggle the state");
             },
+            Stratum => {
+                println!("Starts or stops the Stratum mining server so external miners can work against this node:");
+                println!("stratum [start|stop] [bind address, e.g. 127.0.0.1:3333]");
+            },
+            WorkNotify => {
+                println!("Manages the webhook URLs notified with new block templates as they become available:");
+                println!("work-notify [add|remove|list] [url]");
+            },
+            SetFeePolicy => {
+                println!("Sets and persists the node-wide minimum fee-per-gram; outgoing transactions are floored to");
+                println!("it. Relaying of incoming transactions is not filtered by it:");
+                println!("set-fee-policy [minimum fee-per-gram]");
+            },
             Whoami => {
                 println!(
                     "Display identity information about this node, including: public key, node ID and the public \
@@ -237,9 +419,9 @@ impl Parser {
 
     // Function to process  the get balance command
     fn process_get_balance(&mut self) {
-        let mut handler = self.wallet_output_service.clone();
+        let command_handler = self.command_handler.clone();
         self.executor.spawn(async move {
-            match handler.get_balance().await {
+            match command_handler.get_balance().await {
                 Err(e) => {
                     println!("Something went wrong");
                     warn!(target: LOG_TARGET, "Error communicating with wallet: {}", e.to_string(),);
@@ -252,9 +434,9 @@ impl Parser {
 
     // Function to process  the get chain meta data
     fn process_get_chain_meta(&mut self) {
-        let mut handler = self.node_service.clone();
+        let command_handler = self.command_handler.clone();
         self.executor.spawn(async move {
-            match handler.get_metadata().await {
+            match command_handler.get_chain_metadata().await {
                 Err(err) => {
                     println!("Failed to retrieve chain metadata: {:?}", err);
                     warn!(target: LOG_TARGET, "Error communicating with base node: {}", err,);
@@ -266,10 +448,10 @@ impl Parser {
     }
 
     fn process_list_peers(&self) {
-        let peer_manager = self.peer_manager.clone();
+        let command_handler = self.command_handler.clone();
 
         self.executor.spawn(async move {
-            match peer_manager.flood_peers().await {
+            match command_handler.list_peers().await {
                 Ok(peers) => {
                     let num_peers = peers.len();
                     println!(
@@ -290,20 +472,20 @@ impl Parser {
     }
 
     fn process_list_connections(&self) {
-        let mut connection_manager = self.connection_manager.clone();
+        let command_handler = self.command_handler.clone();
         self.executor.spawn(async move {
-            match connection_manager.get_active_connections().await {
+            match command_handler.list_connections_detailed().await {
                 Ok(conns) if conns.is_empty() => {
                     println!("No active peer connections.");
                 },
                 Ok(conns) => {
                     let num_connections = conns.len();
-                    println!(
-                        "{}",
-                        conns
-                            .into_iter()
-                            .fold(String::new(), |acc, p| { format!("{}\n{}", acc, p) })
-                    );
+                    for conn in &conns {
+                        println!(
+                            "{} {} {} (age: {}s)",
+                            conn.direction, conn.peer_public_key, conn.address, conn.age_seconds
+                        );
+                    }
                     println!("{} active connection(s)", num_connections);
                 },
                 Err(e) => {
@@ -314,26 +496,72 @@ impl Parser {
         });
     }
 
+    fn process_get_network_status(&self) {
+        let command_handler = self.command_handler.clone();
+        self.executor.spawn(async move {
+            match command_handler.get_network_status().await {
+                Ok(status) => {
+                    println!(
+                        "Known: {}, Connected: {}, Active: {}, Max: {}",
+                        status.num_known_peers, status.num_connected_peers, status.num_active_connections, status.max_connections
+                    );
+                    for conn in &status.connections {
+                        println!(
+                            "{} {} {} (age: {}s)",
+                            conn.direction, conn.peer_public_key, conn.address, conn.age_seconds
+                        );
+                    }
+                },
+                Err(e) => {
+                    error!(target: LOG_TARGET, "Could not get network status: {}", e.to_string());
+                    return;
+                },
+            }
+        });
+    }
+
     fn process_toggle_mining(&mut self) {
-        let new_state = !self.enable_miner.load(Ordering::SeqCst);
-        self.enable_miner.store(new_state, Ordering::SeqCst);
+        let new_state = self.command_handler.toggle_mining();
         debug!("Mining state is now switched to {}", new_state);
     }
 
+    // Function to process starting/stopping the Stratum mining server
+    fn process_stratum<'a, I: Iterator<Item = &'a str>>(&mut self, mut args: I) {
+        match args.next() {
+            Some("start") => {
+                let bind_address: SocketAddr = match args.next().unwrap_or("127.0.0.1:3333").parse() {
+                    Ok(addr) => addr,
+                    Err(_) => {
+                        println!("please enter a valid bind address, e.g. 127.0.0.1:3333");
+                        return;
+                    },
+                };
+                match self.executor.block_on(self.command_handler.start_stratum(bind_address)) {
+                    Ok(()) => println!("Stratum server started on {}", bind_address),
+                    Err(e) => {
+                        println!("Failed to start Stratum server: {}", e);
+                        warn!(target: LOG_TARGET, "Failed to start Stratum server: {}", e);
+                    },
+                }
+            },
+            Some("stop") => {
+                if self.executor.block_on(self.command_handler.stop_stratum()) {
+                    println!("Stratum server stopped");
+                } else {
+                    println!("Stratum server is not running");
+                }
+            },
+            _ => {
+                println!("stratum [start|stop] [bind address, e.g. 127.0.0.1:3333]");
+            },
+        }
+    }
+
     fn process_list_headers<'a, I: Iterator<Item = &'a str>>(&self, mut args: I) {
         let max_headers = args.next().unwrap_or("1").parse().unwrap_or(1);
-        let mut handler = self.node_service.clone();
+        let command_handler = self.command_handler.clone();
         self.executor.spawn(async move {
-            let max_height = match handler.get_metadata().await {
-                Err(err) => {
-                    println!("Failed to retrieve chain height: {:?}", err);
-                    warn!(target: LOG_TARGET, "Error communicating with base node: {}", err,);
-                    0
-                },
-                Ok(data) => data.height_of_longest_chain.unwrap_or(0),
-            };
-            let heights = (0..max_height + 1).rev().take(max_headers).collect();
-            let headers = match handler.get_header(heights).await {
+            let headers = match command_handler.list_headers(max_headers).await {
                 Err(err) => {
                     println!("Failed to retrieve headers: {:?}", err);
                     warn!(target: LOG_TARGET, "Error communicating with base node: {}", err,);
@@ -350,52 +578,156 @@ impl Parser {
         });
     }
 
+    // Function to process adding, removing and listing work-notify webhook URLs
+    fn process_work_notify<'a, I: Iterator<Item = &'a str>>(&mut self, mut args: I) {
+        let command_handler = self.command_handler.clone();
+        match args.next() {
+            Some("add") => {
+                let url = match args.next() {
+                    Some(url) => url.to_string(),
+                    None => {
+                        println!("work-notify add [url]");
+                        return;
+                    },
+                };
+                self.executor.spawn(async move {
+                    command_handler.work_notify_add(url.clone()).await;
+                    println!("Added work-notify webhook: {}", url);
+                });
+            },
+            Some("remove") => {
+                let url = match args.next() {
+                    Some(url) => url.to_string(),
+                    None => {
+                        println!("work-notify remove [url]");
+                        return;
+                    },
+                };
+                self.executor.spawn(async move {
+                    if command_handler.work_notify_remove(&url).await {
+                        println!("Removed work-notify webhook: {}", url);
+                    } else {
+                        println!("No such work-notify webhook: {}", url);
+                    }
+                });
+            },
+            Some("list") | None => {
+                self.executor.spawn(async move {
+                    let urls = command_handler.work_notify_list().await;
+                    if urls.is_empty() {
+                        println!("No work-notify webhooks registered");
+                    } else {
+                        println!("{}", urls.join("\n"));
+                    }
+                });
+            },
+            Some(_) => {
+                println!("work-notify [add|remove|list] [url]");
+            },
+        }
+    }
+
     fn process_whoami(&self) {
-        println!("{}", self.node_identity);
+        println!("{}", self.command_handler.whoami());
     }
 
     // Function to process  the send transaction function
     fn process_send_tari<'a, I: Iterator<Item = &'a str>>(&mut self, args: I) {
-        let command_arg = args.take(3).collect::<Vec<&str>>();
-        if command_arg.len() != 3 {
+        let command_arg = args.take(4).collect::<Vec<&str>>();
+        if command_arg.len() < 2 {
             println!("Command entered incorrectly, please use the following format: ");
-            println!("send_tari [amount of tari to send] [public key to send to]");
+            println!("send-tari [amount of tari to send] [public key to send to] [fee-per-gram] [message]");
             return;
         }
-        let amount = command_arg[1].parse::<u64>();
+        let amount = command_arg[0].parse::<u64>();
         if amount.is_err() {
             println!("please enter a valid amount of tari");
             return;
         }
         let amount: MicroTari = amount.unwrap().into();
-        let dest_pubkey =
-            match CommsPublicKey::from_hex(command_arg[2]).or_else(|_| EmojiId::str_to_pubkey(command_arg[2])) {
-                Ok(pk) => pk,
+        let destination = command_arg[1].to_string();
+        let fee_per_gram = match command_arg.get(2) {
+            Some(fee) => match fee.parse::<u64>() {
+                Ok(fee) => fee.into(),
                 Err(_) => {
-                    println!("please enter a valid destination pub_key");
+                    println!("please enter a valid fee-per-gram");
                     return;
                 },
-            };
-        let fee_per_gram = 25 * uT;
-        let mut handler = self.wallet_transaction_service.clone();
+            },
+            None => DEFAULT_FEE_PER_GRAM,
+        };
+        let message = command_arg.get(3).map(|s| s.to_string()).unwrap_or_default();
+        let command_handler = self.command_handler.clone();
         self.executor.spawn(async move {
-            match handler
-                .send_transaction(
-                    dest_pubkey.clone(),
-                    amount,
-                    fee_per_gram,
-                    "coinbase reward from mining".into(),
-                )
-                .await
-            {
+            match command_handler.send_tari(amount, fee_per_gram, &destination, message).await {
+                Err(CommandError::InvalidArgument(_)) => {
+                    println!("please enter a valid destination pub_key");
+                },
                 Err(e) => {
                     println!("Something went wrong sending funds");
                     println!("{:?}", e);
                     warn!(target: LOG_TARGET, "Error communicating with wallet: {}", e.to_string(),);
+                },
+                Ok(result) => println!("Send {} Tari to {} ", result.amount, result.destination),
+            };
+        });
+    }
+
+    // Function to process setting the node-wide minimum fee-per-gram
+    fn process_set_fee_policy<'a, I: Iterator<Item = &'a str>>(&mut self, mut args: I) {
+        let min_fee_per_gram = match args.next() {
+            Some(fee) => match fee.parse::<u64>() {
+                Ok(fee) => MicroTari::from(fee),
+                Err(_) => {
+                    println!("please enter a valid minimum fee-per-gram");
                     return;
                 },
-                Ok(_) => println!("Send {} Tari to {} ", amount, dest_pubkey),
+            },
+            None => {
+                println!("set-fee-policy [minimum fee-per-gram]");
+                return;
+            },
+        };
+        let command_handler = self.command_handler.clone();
+        self.executor.spawn(async move {
+            match command_handler.set_fee_policy(min_fee_per_gram).await {
+                Ok(policy) => println!("Minimum fee-per-gram set to {}", policy.min_fee_per_gram),
+                Err(e) => {
+                    println!("Failed to set fee policy: {}", e);
+                    warn!(target: LOG_TARGET, "Failed to set fee policy: {}", e);
+                },
             };
         });
     }
 }
+
+/// The process exit code for a batch run: `0` if every command in `results` succeeded, `1` if any failed. Split out
+/// from [`Parser::run_batch`] so the rule can be unit tested without driving a full command_handler/executor.
+fn batch_exit_code(results: &[bool]) -> i32 {
+    if results.iter().all(|&success| success) {
+        0
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn batch_exit_code_all_succeeded() {
+        assert_eq!(batch_exit_code(&[true, true, true]), 0);
+    }
+
+    #[test]
+    fn batch_exit_code_empty_is_success() {
+        assert_eq!(batch_exit_code(&[]), 0);
+    }
+
+    #[test]
+    fn batch_exit_code_any_failure() {
+        assert_eq!(batch_exit_code(&[true, false, true]), 1);
+        assert_eq!(batch_exit_code(&[false]), 1);
+    }
+}
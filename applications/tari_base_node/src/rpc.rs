@@ -0,0 +1,284 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! JSON-RPC (HTTP + WebSocket) subsystem exposing every [`BaseNodeCommand`](crate::parser::BaseNodeCommand) that
+//! has a meaningful remote-callable equivalent. This is a thin transport over [`crate::commands::CommandHandler`]
+//! -- the same command layer driven by the interactive [`crate::parser::Parser`] -- so the two front-ends can never
+//! drift in behaviour. `Help` (REPL-only text) and `Quit`/`Exit` (terminates the local process) are deliberately not
+//! exposed here; every other variant, including `Stratum` and `WorkNotify`, has a matching method below.
+
+use crate::commands::CommandHandler;
+use jsonrpc_core::{Error as RpcError, ErrorCode, IoHandler, Params, Value};
+use jsonrpc_http_server::{CloseHandle as HttpCloseHandle, ServerBuilder as HttpServerBuilder};
+use jsonrpc_ws_server::{CloseHandle as WsCloseHandle, ServerBuilder as WsServerBuilder};
+use log::*;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use tari_core::transactions::tari_amount::MicroTari;
+
+pub const LOG_TARGET: &str = "base_node::app::rpc";
+
+/// Handles to the running JSON-RPC listeners, kept alive for as long as the node runs so they can be shut down
+/// cleanly alongside the rest of the node.
+pub struct RpcServerHandles {
+    http_close: HttpCloseHandle,
+    ws_close: WsCloseHandle,
+}
+
+impl RpcServerHandles {
+    pub fn shutdown(self) {
+        self.http_close.close();
+        self.ws_close.close();
+    }
+}
+
+#[derive(Deserialize)]
+struct SendTariParams {
+    amount: u64,
+    destination: String,
+    #[serde(default)]
+    fee_per_gram: Option<u64>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListHeadersParams {
+    #[serde(default = "default_num_headers")]
+    num_headers: usize,
+}
+
+fn default_num_headers() -> usize {
+    1
+}
+
+#[derive(Deserialize)]
+struct StratumStartParams {
+    bind_address: SocketAddr,
+}
+
+#[derive(Deserialize)]
+struct WorkNotifyUrlParams {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct SetFeePolicyParams {
+    min_fee_per_gram: u64,
+}
+
+fn rpc_error(err: impl ToString) -> RpcError {
+    RpcError {
+        code: ErrorCode::ServerError(-32000),
+        message: err.to_string(),
+        data: None,
+    }
+}
+
+fn params_error(err: impl ToString) -> RpcError {
+    RpcError {
+        code: ErrorCode::InvalidParams,
+        message: err.to_string(),
+        data: None,
+    }
+}
+
+/// Builds the `IoHandler` with the methods for every remote-callable `BaseNodeCommand`, shared by both the HTTP and
+/// WebSocket listeners.
+fn build_io_handler(command_handler: CommandHandler) -> IoHandler {
+    let mut io = IoHandler::new();
+
+    let handler = command_handler.clone();
+    io.add_method("get_balance", move |_params: Params| {
+        let handler = handler.clone();
+        async move {
+            let balance = handler.get_balance().await.map_err(rpc_error)?;
+            Ok(serde_json::to_value(balance).map_err(rpc_error)?)
+        }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("get_chain_metadata", move |_params: Params| {
+        let handler = handler.clone();
+        async move {
+            let meta = handler.get_chain_metadata().await.map_err(rpc_error)?;
+            Ok(serde_json::to_value(meta).map_err(rpc_error)?)
+        }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("list_peers", move |_params: Params| {
+        let handler = handler.clone();
+        async move {
+            let peers = handler.list_peers().await.map_err(rpc_error)?;
+            let peers: Vec<String> = peers.into_iter().map(|p| p.to_string()).collect();
+            Ok(serde_json::json!({ "count": peers.len(), "peers": peers }))
+        }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("list_connections", move |_params: Params| {
+        let handler = handler.clone();
+        async move {
+            let conns = handler.list_connections_detailed().await.map_err(rpc_error)?;
+            Ok(serde_json::to_value(conns).map_err(rpc_error)?)
+        }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("list_headers", move |params: Params| {
+        let handler = handler.clone();
+        async move {
+            let params: ListHeadersParams = params.parse().unwrap_or(ListHeadersParams { num_headers: 1 });
+            let headers = handler.list_headers(params.num_headers).await.map_err(rpc_error)?;
+            let headers: Vec<String> = headers.into_iter().map(|h| format!("{}", h)).collect();
+            Ok(Value::from(headers))
+        }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("whoami", move |_params: Params| {
+        let handler = handler.clone();
+        async move { Ok(Value::String(handler.whoami().to_string())) }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("toggle_mining", move |_params: Params| {
+        let handler = handler.clone();
+        async move { Ok(Value::Bool(handler.toggle_mining())) }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("send_tari", move |params: Params| {
+        let handler = handler.clone();
+        async move {
+            let params: SendTariParams = params.parse().map_err(params_error)?;
+            let fee_per_gram = params
+                .fee_per_gram
+                .map(Into::into)
+                .unwrap_or(crate::commands::DEFAULT_FEE_PER_GRAM);
+            let result = handler
+                .send_tari(
+                    params.amount.into(),
+                    fee_per_gram,
+                    &params.destination,
+                    params.message.unwrap_or_else(|| "".into()),
+                )
+                .await
+                .map_err(rpc_error)?;
+            Ok(serde_json::to_value(result).map_err(rpc_error)?)
+        }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("get_network_status", move |_params: Params| {
+        let handler = handler.clone();
+        async move {
+            let status = handler.get_network_status().await.map_err(rpc_error)?;
+            Ok(serde_json::to_value(status).map_err(rpc_error)?)
+        }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("start_stratum", move |params: Params| {
+        let handler = handler.clone();
+        async move {
+            let params: StratumStartParams = params.parse().map_err(params_error)?;
+            handler.start_stratum(params.bind_address).await.map_err(rpc_error)?;
+            Ok(Value::Bool(true))
+        }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("stop_stratum", move |_params: Params| {
+        let handler = handler.clone();
+        async move { Ok(Value::Bool(handler.stop_stratum().await)) }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("work_notify_add", move |params: Params| {
+        let handler = handler.clone();
+        async move {
+            let params: WorkNotifyUrlParams = params.parse().map_err(params_error)?;
+            handler.work_notify_add(params.url).await;
+            Ok(Value::Bool(true))
+        }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("work_notify_remove", move |params: Params| {
+        let handler = handler.clone();
+        async move {
+            let params: WorkNotifyUrlParams = params.parse().map_err(params_error)?;
+            Ok(Value::Bool(handler.work_notify_remove(&params.url).await))
+        }
+    });
+
+    let handler = command_handler.clone();
+    io.add_method("work_notify_list", move |_params: Params| {
+        let handler = handler.clone();
+        async move { Ok(Value::from(handler.work_notify_list().await)) }
+    });
+
+    let handler = command_handler;
+    io.add_method("set_fee_policy", move |params: Params| {
+        let handler = handler.clone();
+        async move {
+            let params: SetFeePolicyParams = params.parse().map_err(params_error)?;
+            let policy = handler
+                .set_fee_policy(MicroTari::from(params.min_fee_per_gram))
+                .await
+                .map_err(rpc_error)?;
+            Ok(serde_json::to_value(policy).map_err(rpc_error)?)
+        }
+    });
+
+    io
+}
+
+/// Starts the HTTP and WebSocket JSON-RPC listeners. Both serve the same method set over the same
+/// [`CommandHandler`], so a caller can pick whichever transport suits it.
+pub fn start(
+    http_address: SocketAddr,
+    ws_address: SocketAddr,
+    command_handler: CommandHandler,
+) -> Result<RpcServerHandles, std::io::Error> {
+    let io = build_io_handler(command_handler);
+
+    let http_server = HttpServerBuilder::new(io.clone())
+        .start_http(&http_address)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+    info!(target: LOG_TARGET, "JSON-RPC HTTP server listening on {}", http_address);
+    let http_close = http_server.close_handle();
+    std::thread::spawn(move || http_server.wait());
+
+    let ws_server = WsServerBuilder::new(io)
+        .start(&ws_address)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+    info!(target: LOG_TARGET, "JSON-RPC WebSocket server listening on {}", ws_address);
+    let ws_close = ws_server.close_handle();
+    std::thread::spawn(move || {
+        let _ = ws_server.wait();
+    });
+
+    Ok(RpcServerHandles { http_close, ws_close })
+}
@@ -0,0 +1,272 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! A minimal Stratum subsystem so external miners and pools can work against this node, as an alternative to the
+//! built-in miner toggled by `ToggleMining`. Clients connect over TCP and exchange line-delimited JSON-RPC: they
+//! `mining.subscribe` and `mining.authorize`, the server pushes `mining.notify` whenever the shared
+//! [`crate::chain_tip::ChainTipWatcher`] observes a new chain tip, and clients `mining.submit` completed nonces back.
+//! The most recently published job is also cached and sent immediately on `mining.subscribe`, so a worker that
+//! connects between chain-tip changes isn't left idle until the next one.
+//! A submission is only ever acknowledged once its proof of work has actually been checked against the job it
+//! claims to solve; an invalid or stale submission is nacked rather than silently accepted.
+
+use crate::{
+    chain_tip::{ChainTipEvent, ChainTipWatcher},
+    LOG_TARGET,
+};
+use futures::{SinkExt, StreamExt};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+use tari_core::{
+    base_node::LocalNodeCommsInterface,
+    blocks::{Block, NewBlockTemplate},
+    proof_of_work::{sha3_difficulty, Difficulty},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, Mutex, RwLock},
+};
+use tokio_util::codec::{Framed, LinesCodec};
+
+/// A `mining.notify` job derived from the node's current block template.
+#[derive(Clone, Debug, Serialize)]
+pub struct StratumJob {
+    pub job_id: String,
+    pub previous_hash: String,
+    pub template: NewBlockTemplate,
+    pub target_difficulty: Difficulty,
+    pub clean_jobs: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+enum StratumRequest {
+    #[serde(rename = "mining.subscribe")]
+    Subscribe { user_agent: Option<String> },
+    #[serde(rename = "mining.authorize")]
+    Authorize { worker: String, password: String },
+    #[serde(rename = "mining.submit")]
+    Submit { worker: String, job_id: String, nonce: u64 },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum StratumResponse {
+    Ack { result: bool },
+    Notify { method: &'static str, params: StratumJob },
+    Error { error: String },
+}
+
+/// Handle to a running Stratum server, used to stop it from `process_stratum`.
+pub struct StratumServerHandle {
+    shutdown: broadcast::Sender<()>,
+}
+
+impl StratumServerHandle {
+    pub fn stop(&self) {
+        let _ = self.shutdown.send(());
+    }
+}
+
+/// Starts the Stratum TCP listener on `bind_address`. `watcher` is the same chain-tip watcher shared with
+/// `work-notify`, so both subsystems learn about a new tip from a single poll rather than each polling
+/// `node_service` themselves. `node_service` is used to submit blocks assembled from accepted shares.
+pub async fn start(
+    bind_address: SocketAddr,
+    watcher: ChainTipWatcher,
+    node_service: LocalNodeCommsInterface,
+) -> Result<StratumServerHandle, std::io::Error> {
+    let listener = TcpListener::bind(bind_address).await?;
+    info!(target: LOG_TARGET, "Stratum server listening on {}", bind_address);
+
+    let (job_tx, _) = broadcast::channel::<StratumJob>(16);
+    let (shutdown_tx, _) = broadcast::channel::<()>(1);
+    let latest_job: Arc<RwLock<Option<StratumJob>>> = Arc::new(RwLock::new(None));
+
+    tokio::spawn(republish_jobs(watcher.subscribe(), job_tx.clone(), latest_job.clone()));
+
+    let mut shutdown_rx = shutdown_tx.subscribe();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = shutdown_rx.recv() => {
+                    info!(target: LOG_TARGET, "Stratum server shutting down");
+                    break;
+                },
+                Ok((stream, peer)) = listener.accept() => {
+                    let job_rx = job_tx.subscribe();
+                    tokio::spawn(handle_connection(stream, peer, job_rx, latest_job.clone(), node_service.clone()));
+                },
+            }
+        }
+    });
+
+    Ok(StratumServerHandle { shutdown: shutdown_tx })
+}
+
+/// Turns chain-tip events from the shared watcher into `mining.notify` jobs, broadcasting each one and caching it
+/// in `latest_job` so a newly (re)connected worker can be given work immediately instead of waiting for the next
+/// chain-tip change. The first job published after the server starts is sent with `clean_jobs: false` since there
+/// is no prior job for a worker to discard; every later job, and the cached copy handed to a newly connected
+/// worker, carries the flag it was published with.
+async fn republish_jobs(
+    mut tip_rx: broadcast::Receiver<ChainTipEvent>,
+    job_tx: broadcast::Sender<StratumJob>,
+    latest_job: Arc<RwLock<Option<StratumJob>>>,
+) {
+    let mut seen_first = false;
+    loop {
+        let event = match tip_rx.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let job = StratumJob {
+            job_id: format!("{:x}", event.metadata.height_of_longest_chain.unwrap_or(0)),
+            previous_hash: event.metadata.best_block.clone().map(|h| h.to_hex()).unwrap_or_default(),
+            target_difficulty: event.template.header.pow.target_difficulty,
+            template: event.template,
+            clean_jobs: seen_first,
+        };
+        seen_first = true;
+        *latest_job.write().await = Some(job.clone());
+        let _ = job_tx.send(job);
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    mut job_rx: broadcast::Receiver<StratumJob>,
+    latest_job: Arc<RwLock<Option<StratumJob>>>,
+    mut node_service: LocalNodeCommsInterface,
+) {
+    debug!(target: LOG_TARGET, "Stratum worker connected from {}", peer);
+    let framed = Framed::new(stream, LinesCodec::new());
+    let (mut sink, mut lines) = framed.split();
+    let worker = Arc::new(Mutex::new(None::<String>));
+    let mut current_job: Option<StratumJob> = None;
+
+    loop {
+        tokio::select! {
+            line = lines.next() => {
+                let line = match line {
+                    Some(Ok(line)) => line,
+                    _ => break,
+                };
+                let request: StratumRequest = match serde_json::from_str(&line) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        let _ = send(&mut sink, &StratumResponse::Error { error: err.to_string() }).await;
+                        continue;
+                    },
+                };
+                match request {
+                    StratumRequest::Subscribe { .. } => {
+                        let _ = send(&mut sink, &StratumResponse::Ack { result: true }).await;
+                        if let Some(job) = latest_job.read().await.clone() {
+                            current_job = Some(job.clone());
+                            let _ = send(&mut sink, &StratumResponse::Notify { method: "mining.notify", params: job }).await;
+                        }
+                    },
+                    StratumRequest::Authorize { worker: name, .. } => {
+                        *worker.lock().await = Some(name);
+                        let _ = send(&mut sink, &StratumResponse::Ack { result: true }).await;
+                    },
+                    StratumRequest::Submit { worker: name, job_id, nonce } => {
+                        let accepted = submit_share(&mut node_service, &current_job, &job_id, nonce).await;
+                        debug!(
+                            target: LOG_TARGET,
+                            "Stratum worker {} submitted nonce {} for job {}: {}",
+                            name, nonce, job_id, if accepted { "accepted" } else { "rejected" }
+                        );
+                        let _ = send(&mut sink, &StratumResponse::Ack { result: accepted }).await;
+                    },
+                }
+            },
+            Ok(job) = job_rx.recv() => {
+                current_job = Some(job.clone());
+                let _ = send(&mut sink, &StratumResponse::Notify { method: "mining.notify", params: job }).await;
+            },
+            else => break,
+        }
+    }
+    debug!(target: LOG_TARGET, "Stratum worker {} disconnected", peer);
+}
+
+/// Validates a submitted nonce against the job it claims to solve and, if the resulting proof of work meets the
+/// job's target difficulty, assembles the block and submits it to the node. Returns whether the share was accepted.
+/// A `job_id` that doesn't match the worker's current job (stale or unknown) is rejected without touching the node.
+async fn submit_share(
+    node_service: &mut LocalNodeCommsInterface,
+    current_job: &Option<StratumJob>,
+    job_id: &str,
+    nonce: u64,
+) -> bool {
+    let job = match current_job {
+        Some(job) if job.job_id == job_id => job,
+        _ => {
+            debug!(target: LOG_TARGET, "Rejected share for unknown or stale job {}", job_id);
+            return false;
+        },
+    };
+
+    let mut header = job.template.header.clone();
+    header.nonce = nonce;
+    let achieved_difficulty = sha3_difficulty(&header);
+    if achieved_difficulty < job.target_difficulty {
+        debug!(
+            target: LOG_TARGET,
+            "Rejected share for job {}: achieved difficulty {} is below target {}",
+            job_id, achieved_difficulty, job.target_difficulty
+        );
+        return false;
+    }
+
+    let block = assemble_block(&job.template, header);
+    match node_service.submit_block(block).await {
+        Ok(_) => {
+            info!(target: LOG_TARGET, "Submitted block for job {} at difficulty {}", job_id, achieved_difficulty);
+            true
+        },
+        Err(err) => {
+            warn!(target: LOG_TARGET, "Node rejected submitted block for job {}: {}", job_id, err);
+            false
+        },
+    }
+}
+
+async fn send(
+    sink: &mut futures::stream::SplitSink<Framed<TcpStream, LinesCodec>, String>,
+    response: &StratumResponse,
+) -> Result<(), std::io::Error> {
+    let payload = serde_json::to_string(response).unwrap_or_default();
+    sink.send(payload)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+}
+
+/// Assembles a full block from the job's template body and the header carrying the winning nonce.
+fn assemble_block(template: &NewBlockTemplate, header: tari_core::blocks::BlockHeader) -> Block {
+    Block::new(header, template.body.clone())
+}
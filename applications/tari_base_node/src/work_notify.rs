@@ -0,0 +1,159 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Push-based work notification: whenever the shared [`crate::chain_tip::ChainTipWatcher`] observes a new
+//! longest-chain tip, the fresh block template is POSTed as JSON to every registered webhook URL. This lets
+//! external miners and pools react immediately to new work instead of polling `get-chain-metadata`, and is the HTTP
+//! counterpart to the Stratum `mining.notify` job pushed over TCP by [`crate::stratum`].
+
+use crate::{chain_tip::ChainTipWatcher, LOG_TARGET};
+use log::*;
+use serde::Serialize;
+use tari_core::tari_utilities::hex::Hex;
+use tokio::sync::{broadcast, RwLock};
+
+/// The JSON payload POSTed to each registered webhook when a new block template becomes available.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkNotification {
+    pub height: u64,
+    pub previous_hash: String,
+    pub target_difficulty: u64,
+    pub timestamp: u64,
+    pub header_bytes: Vec<u8>,
+}
+
+/// Keeps the list of registered webhook URLs and forwards every new block template to each of them. One instance
+/// is shared between the `work-notify` command (which edits the URL list) and the background task that watches
+/// the shared chain tip for new templates.
+#[derive(Clone)]
+pub struct WorkNotifier {
+    urls: std::sync::Arc<RwLock<Vec<String>>>,
+    http_client: reqwest::Client,
+}
+
+/// How long to wait for a single webhook POST before giving up on it. Keeps one slow or unresponsive URL from
+/// holding up this task's handling of the next chain-tip event.
+const WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+impl WorkNotifier {
+    pub fn new() -> Self {
+        Self {
+            urls: std::sync::Arc::new(RwLock::new(Vec::new())),
+            http_client: reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .expect("reqwest client config is valid"),
+        }
+    }
+
+    pub async fn add_url(&self, url: String) {
+        self.urls.write().await.push(url);
+    }
+
+    pub async fn remove_url(&self, url: &str) -> bool {
+        let mut urls = self.urls.write().await;
+        let len_before = urls.len();
+        urls.retain(|u| u != url);
+        urls.len() != len_before
+    }
+
+    pub async fn list_urls(&self) -> Vec<String> {
+        self.urls.read().await.clone()
+    }
+
+    /// POSTs `work` to every registered webhook concurrently, so one slow or unresponsive URL (bounded by
+    /// `WEBHOOK_TIMEOUT`) cannot delay delivery to the others.
+    async fn notify(&self, work: &WorkNotification) {
+        let urls = self.urls.read().await.clone();
+        let posts = urls.into_iter().map(|url| {
+            let http_client = self.http_client.clone();
+            async move {
+                if let Err(e) = http_client.post(&url).json(work).send().await {
+                    warn!(target: LOG_TARGET, "Failed to notify work webhook {}: {}", url, e);
+                }
+            }
+        });
+        futures::future::join_all(posts).await;
+    }
+
+    /// Subscribes to `watcher` and notifies every registered webhook whenever it reports a new chain tip. Runs
+    /// until `shutdown` fires.
+    pub async fn run(self, watcher: ChainTipWatcher, mut shutdown: broadcast::Receiver<()>) {
+        let mut tip_rx = watcher.subscribe();
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                event = tip_rx.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    let work = WorkNotification {
+                        height: event.metadata.height_of_longest_chain.unwrap_or(0),
+                        previous_hash: event.metadata.best_block.map(|h| h.to_hex()).unwrap_or_default(),
+                        target_difficulty: event.template.header.pow.target_difficulty.as_u64(),
+                        timestamp: event.template.header.timestamp.as_u64(),
+                        header_bytes: event.template.header.to_bytes(),
+                    };
+                    self.notify(&work).await;
+                },
+            }
+        }
+    }
+}
+
+impl Default for WorkNotifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn add_list_remove_url() {
+        let notifier = WorkNotifier::new();
+        assert!(notifier.list_urls().await.is_empty());
+
+        notifier.add_url("http://pool.example/hook".to_string()).await;
+        notifier.add_url("http://pool2.example/hook".to_string()).await;
+        assert_eq!(notifier.list_urls().await, vec![
+            "http://pool.example/hook".to_string(),
+            "http://pool2.example/hook".to_string(),
+        ]);
+
+        assert!(notifier.remove_url("http://pool.example/hook").await);
+        assert_eq!(notifier.list_urls().await, vec!["http://pool2.example/hook".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn remove_url_not_present_returns_false() {
+        let notifier = WorkNotifier::new();
+        notifier.add_url("http://pool.example/hook".to_string()).await;
+
+        assert!(!notifier.remove_url("http://unknown.example/hook").await);
+        assert_eq!(notifier.list_urls().await, vec!["http://pool.example/hook".to_string()]);
+    }
+}